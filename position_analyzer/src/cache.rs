@@ -0,0 +1,86 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::Result;
+use chess::Board;
+use serde::{Deserialize, Serialize};
+
+use crate::TopMove;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CacheRecord {
+    key: u64,
+    top_moves: Vec<TopMove>,
+}
+
+/// Combines the position's Zobrist hash (which already covers piece
+/// placement, castling rights, en-passant file, and side-to-move - see
+/// `chess::Board::get_hash`) with the Elo the position was analyzed at, so
+/// a curriculum sweep doesn't collide different strengths of the same FEN.
+pub fn position_key(board: &Board, elo: Option<u32>) -> u64 {
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    board.get_hash().hash(&mut hasher);
+    elo.hash(&mut hasher);
+    hasher.finish()
+}
+
+struct CacheState {
+    entries: HashMap<u64, Vec<TopMove>>,
+    file: Option<File>,
+}
+
+/// A persistent, append-only analysis cache shared across the rayon loop.
+/// Hits avoid a Stockfish search entirely; misses are analyzed normally and
+/// appended to disk so later runs (or overlapping datasets) reuse them.
+pub struct AnalysisCache {
+    state: Mutex<CacheState>,
+}
+
+impl AnalysisCache {
+    /// Loads existing entries from `path` (when `reuse` is true and the
+    /// file exists), then reopens it in append mode so new entries accumulate
+    /// on disk. With `reuse` false, the file is still appended to but its
+    /// prior contents are ignored for this run.
+    pub fn load(path: Option<&str>, reuse: bool) -> Result<Self> {
+        let mut entries = HashMap::new();
+
+        if let Some(path) = path {
+            if reuse && Path::new(path).exists() {
+                let file = File::open(path)?;
+                for line in BufReader::new(file).lines() {
+                    let line = line?;
+                    if let Ok(record) = serde_json::from_str::<CacheRecord>(&line) {
+                        entries.insert(record.key, record.top_moves);
+                    }
+                }
+            }
+        }
+
+        let file = match path {
+            Some(path) => Some(OpenOptions::new().create(true).append(true).open(path)?),
+            None => None,
+        };
+
+        Ok(AnalysisCache { state: Mutex::new(CacheState { entries, file }) })
+    }
+
+    pub fn get(&self, key: u64) -> Option<Vec<TopMove>> {
+        self.state.lock().unwrap().entries.get(&key).cloned()
+    }
+
+    pub fn insert(&self, key: u64, top_moves: &[TopMove]) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        state.entries.insert(key, top_moves.to_vec());
+
+        if let Some(file) = state.file.as_mut() {
+            let record = CacheRecord { key, top_moves: top_moves.to_vec() };
+            writeln!(file, "{}", serde_json::to_string(&record)?)?;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,223 @@
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::{TopMove, Wdl};
+
+/// Static UCI options applied once at engine startup, typically sourced
+/// from CLI flags or a small JSON config file.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct EngineOptions {
+    #[serde(default)]
+    pub threads: Option<u32>,
+    #[serde(default)]
+    pub hash_mb: Option<u32>,
+    #[serde(default)]
+    pub multipv: Option<u32>,
+    #[serde(default)]
+    pub skill_level: Option<u32>,
+    #[serde(default)]
+    pub show_wdl: Option<bool>,
+}
+
+/// A long-lived Stockfish child process that has completed the UCI
+/// handshake and can be reused across many positions via `ucinewgame`
+/// instead of being respawned per-position.
+pub struct Engine {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: BufReader<ChildStdout>,
+    multipv: u32,
+    current_elo: Option<u32>,
+}
+
+impl Engine {
+    /// Spawns Stockfish, applies `options` via `setoption`, and performs
+    /// `uci` -> `uciok`, `isready` -> `readyok` once up front so later
+    /// per-position analysis doesn't race the engine.
+    pub fn spawn(stockfish_path: &str, options: &EngineOptions) -> Result<Self> {
+        let mut child = Command::new(stockfish_path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .with_context(|| format!("failed to spawn stockfish at {}", stockfish_path))?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+
+        let mut engine = Engine {
+            child,
+            stdin,
+            stdout,
+            multipv: options.multipv.unwrap_or(5),
+            current_elo: None,
+        };
+        engine.send("uci")?;
+        engine.read_until(|line| line == "uciok")?;
+
+        if let Some(threads) = options.threads {
+            engine.send(&format!("setoption name Threads value {}", threads))?;
+        }
+        if let Some(hash_mb) = options.hash_mb {
+            engine.send(&format!("setoption name Hash value {}", hash_mb))?;
+        }
+        if let Some(multipv) = options.multipv {
+            engine.send(&format!("setoption name MultiPV value {}", multipv))?;
+        }
+        if let Some(skill_level) = options.skill_level {
+            engine.send(&format!("setoption name Skill Level value {}", skill_level))?;
+        }
+        if options.show_wdl.unwrap_or(false) {
+            engine.send("setoption name UCI_ShowWDL value true")?;
+        }
+
+        engine.send("isready")?;
+        engine.read_until(|line| line == "readyok")?;
+
+        Ok(engine)
+    }
+
+    fn send(&mut self, command: &str) -> Result<()> {
+        writeln!(self.stdin, "{}", command)?;
+        self.stdin.flush()?;
+        Ok(())
+    }
+
+    /// Reads lines until one satisfies `is_terminal`, returning everything
+    /// read (inclusive of the terminal line).
+    fn read_until(&mut self, mut is_terminal: impl FnMut(&str) -> bool) -> Result<Vec<String>> {
+        let mut lines = Vec::new();
+        loop {
+            let mut line = String::new();
+            let bytes_read = self.stdout.read_line(&mut line)?;
+            if bytes_read == 0 {
+                break;
+            }
+            let line = line.trim_end().to_string();
+            let terminal = is_terminal(&line);
+            lines.push(line);
+            if terminal {
+                break;
+            }
+        }
+        Ok(lines)
+    }
+
+    /// Sends `UCI_LimitStrength`/`UCI_Elo` only when the requested Elo
+    /// differs from what's already set, so a curriculum sweep over the same
+    /// engine doesn't re-send `setoption` for every single position.
+    fn set_elo(&mut self, elo: Option<u32>) -> Result<()> {
+        if self.current_elo == elo {
+            return Ok(());
+        }
+        match elo {
+            Some(target) => {
+                self.send("setoption name UCI_LimitStrength value true")?;
+                self.send(&format!("setoption name UCI_Elo value {}", target))?;
+            }
+            None => {
+                self.send("setoption name UCI_LimitStrength value false")?;
+            }
+        }
+        self.current_elo = elo;
+        Ok(())
+    }
+
+    /// Analyzes a single FEN on this engine, resetting search state with
+    /// `ucinewgame` first so earlier positions can't bleed into this one.
+    /// `elo` optionally conditions the search strength via
+    /// `UCI_LimitStrength`/`UCI_Elo` for curriculum-style data generation.
+    pub fn analyze(&mut self, fen: &str, depth: u8, elo: Option<u32>) -> Result<Vec<TopMove>> {
+        self.set_elo(elo)?;
+        self.send("ucinewgame")?;
+        self.send(&format!("position fen {}", fen))?;
+        self.send("isready")?;
+        self.read_until(|line| line == "readyok")?;
+        self.send(&format!("go depth {} multipv {}", depth, self.multipv))?;
+
+        let mut top_moves = Vec::new();
+        loop {
+            let mut line = String::new();
+            let bytes_read = self.stdout.read_line(&mut line)?;
+            if bytes_read == 0 {
+                break;
+            }
+            let line = line.trim_end();
+
+            if line.starts_with("bestmove") {
+                break;
+            }
+
+            if line.contains("depth") && line.contains("multipv") && line.contains("score") {
+                let parts: Vec<&str> = line.split_whitespace().collect();
+
+                if let (Some(pv_idx), Some(score_idx), Some(pv_kw_idx)) = (
+                    parts.iter().position(|&x| x == "multipv"),
+                    parts.iter().position(|&x| x == "score"),
+                    parts.iter().position(|&x| x == "pv"),
+                ) {
+                    if score_idx + 2 < parts.len() && pv_kw_idx + 1 < parts.len() {
+                        let score_type = parts[score_idx + 1];
+                        let score_val = parts[score_idx + 2];
+
+                        let (eval_cp, mate) = if score_type == "cp" {
+                            (Some(score_val.parse::<i32>().unwrap_or(0)), None)
+                        } else if score_type == "mate" {
+                            (None, Some(score_val.parse::<i32>().unwrap_or(0)))
+                        } else {
+                            (None, None)
+                        };
+
+                        let wdl = parts.iter().position(|&x| x == "wdl").and_then(|wdl_idx| {
+                            let win = parts.get(wdl_idx + 1)?.parse().ok()?;
+                            let draw = parts.get(wdl_idx + 2)?.parse().ok()?;
+                            let loss = parts.get(wdl_idx + 3)?.parse().ok()?;
+                            Some(Wdl { win, draw, loss })
+                        });
+
+                        let pv: Vec<String> =
+                            parts[pv_kw_idx + 1..].iter().map(|s| s.to_string()).collect();
+
+                        let multipv_num = parts
+                            .get(pv_idx + 1)
+                            .and_then(|s| s.parse::<usize>().ok())
+                            .unwrap_or(0);
+
+                        if multipv_num > 0 && multipv_num <= self.multipv as usize && !pv.is_empty() {
+                            if top_moves.len() < multipv_num {
+                                top_moves.resize(
+                                    multipv_num,
+                                    TopMove {
+                                        r#move: String::new(),
+                                        eval_cp: None,
+                                        mate: None,
+                                        pv: Vec::new(),
+                                        wdl: None,
+                                    },
+                                );
+                            }
+                            top_moves[multipv_num - 1] = TopMove {
+                                r#move: pv[0].clone(),
+                                eval_cp,
+                                mate,
+                                pv,
+                                wdl,
+                            };
+                        }
+                    }
+                }
+            }
+        }
+
+        Ok(top_moves)
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        self.send("quit").ok();
+        self.child.wait().ok();
+    }
+}
@@ -1,6 +1,8 @@
-use std::fs::File;
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
 use std::io::{BufRead, BufReader, Write};
-use std::process::{Command, Stdio};
+use std::path::Path;
 use std::sync::{Arc, Mutex};
 use anyhow::Result;
 use chess::{Board, ChessMove, MoveGen};
@@ -9,16 +11,67 @@ use serde::{Deserialize, Serialize};
 use indicatif::{ProgressBar, ProgressStyle};
 use std::str::FromStr;
 
+mod cache;
+mod engine;
+mod pgn;
+
+use cache::AnalysisCache;
+use engine::{Engine, EngineOptions};
+
+/// Generation-time knobs beyond the engine's static UCI options: the Elo
+/// curriculum to sweep over the position set. An empty `elo_levels` means
+/// analyze at full engine strength with no `UCI_LimitStrength`.
+#[derive(Debug, Clone, Default, Deserialize)]
+struct GenerationConfig {
+    #[serde(flatten)]
+    engine: EngineOptions,
+    #[serde(default)]
+    elo_levels: Vec<u32>,
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 struct Position {
     fen: String,
     phase: String,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Wdl {
+    win: i32,
+    draw: i32,
+    loss: i32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct TopMove {
     r#move: String,
-    eval_cp: i32,
+    eval_cp: Option<i32>,
+    mate: Option<i32>,
+    pv: Vec<String>,
+    wdl: Option<Wdl>,
+}
+
+/// A usable scalar eval for ranking/numeric purposes (value targets,
+/// softmax policy) even for mate scores, which have no natural centipawn
+/// magnitude: mates saturate to ±10000 by sign, same as engines that clamp.
+fn effective_cp(m: &TopMove) -> i32 {
+    match (m.eval_cp, m.mate) {
+        (Some(cp), _) => cp,
+        (None, Some(mate)) if mate > 0 => 10000,
+        (None, Some(_)) => -10000,
+        (None, None) => 0,
+    }
+}
+
+/// Renders a move's score as `+35cp`, `mate in 4`, or `mated in 4`
+/// (positive mate distance is a forced mate for the side to move).
+fn describe_score(m: &TopMove) -> String {
+    match (m.eval_cp, m.mate) {
+        (Some(cp), _) => format!("{:+}cp", cp),
+        (None, Some(mate)) if mate > 0 => format!("mate in {}", mate),
+        (None, Some(mate)) => format!("mated in {}", -mate),
+        (None, None) => "unknown".to_string(),
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -29,7 +82,36 @@ struct Message {
 
 #[derive(Debug, Serialize)]
 struct TrainingExample {
+    fen: String,
     messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    elo: Option<u32>,
+}
+
+#[derive(Debug, Serialize)]
+struct PolicyEntry {
+    r#move: String,
+    prob: f64,
+}
+
+/// Training record for a policy/value network: a scalar value target plus
+/// a move distribution, instead of a chat-style prompt/response pair.
+#[derive(Debug, Serialize)]
+struct PolicyValueExample {
+    fen: String,
+    phase: String,
+    value: f64,
+    policy: Vec<PolicyEntry>,
+    material: i32,
+    legal_moves: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    elo: Option<u32>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputMode {
+    Chat,
+    PolicyValue,
 }
 
 fn count_material(board: &Board) -> i32 {
@@ -49,75 +131,27 @@ fn count_material(board: &Board) -> i32 {
     stm_material - opp_material
 }
 
-fn analyze_position(fen: &str, stockfish_path: &str, depth: u8) -> Result<Vec<TopMove>> {
-    let mut child = Command::new(stockfish_path)
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()?;
-    
-    let stdin = child.stdin.as_mut().unwrap();
-    stdin.write_all(b"uci\n")?;
-    stdin.write_all(format!("position fen {}\n", fen).as_bytes())?;
-    stdin.write_all(format!("go depth {} multipv 5\n", depth).as_bytes())?;
-    
-    let stdout = child.stdout.take().unwrap();
-    let reader = BufReader::new(stdout);
-    
-    let mut top_moves = Vec::new();
-    
-    for line in reader.lines() {
-        let line = line?;
-        
-        if line.starts_with("bestmove") {
-            break;
-        }
-        
-        if line.contains("depth") && line.contains("multipv") && line.contains("score") {
-            let parts: Vec<&str> = line.split_whitespace().collect();
-            
-            if let (Some(pv_idx), Some(score_idx), Some(move_idx)) = (
-                parts.iter().position(|&x| x == "multipv"),
-                parts.iter().position(|&x| x == "score"),
-                parts.iter().position(|&x| x == "pv")
-            ) {
-                if score_idx + 2 < parts.len() && move_idx + 1 < parts.len() {
-                    let score_type = parts[score_idx + 1];
-                    let score_val = parts[score_idx + 2];
-                    let best_move = parts[move_idx + 1];
-                    
-                    let eval_cp = if score_type == "cp" {
-                        score_val.parse::<i32>().unwrap_or(0)
-                    } else if score_type == "mate" {
-                        let mate_in = score_val.parse::<i32>().unwrap_or(0);
-                        if mate_in > 0 { 10000 } else { -10000 }
-                    } else {
-                        0
-                    };
-                    
-                    let multipv_num = parts.get(pv_idx + 1)
-                        .and_then(|s| s.parse::<usize>().ok())
-                        .unwrap_or(0);
-                    
-                    if multipv_num > 0 && multipv_num <= 5 {
-                        if top_moves.len() < multipv_num {
-                            top_moves.resize(multipv_num, TopMove {
-                                r#move: String::new(),
-                                eval_cp: 0,
-                            });
-                        }
-                        top_moves[multipv_num - 1] = TopMove {
-                            r#move: best_move.to_string(),
-                            eval_cp,
-                        };
-                    }
-                }
-            }
+thread_local! {
+    /// One Stockfish process per rayon worker thread, kept alive across
+    /// positions instead of respawned per-FEN. Lazily spawned (and
+    /// UCI-handshaked) on that thread's first call to `analyze_position`.
+    static ENGINE: RefCell<Option<Engine>> = RefCell::new(None);
+}
+
+fn analyze_position(
+    fen: &str,
+    stockfish_path: &str,
+    depth: u8,
+    options: &EngineOptions,
+    elo: Option<u32>,
+) -> Result<Vec<TopMove>> {
+    ENGINE.with(|cell| {
+        let mut slot = cell.borrow_mut();
+        if slot.is_none() {
+            *slot = Some(Engine::spawn(stockfish_path, options)?);
         }
-    }
-    
-    child.kill().ok();
-    
-    Ok(top_moves)
+        slot.as_mut().unwrap().analyze(fen, depth, elo)
+    })
 }
 
 fn create_training_example(
@@ -125,6 +159,7 @@ fn create_training_example(
     fen: &str,
     phase: &str,
     top_moves: &[TopMove],
+    elo: Option<u32>,
 ) -> Result<TrainingExample> {
     let legal_moves: Vec<String> = MoveGen::new_legal(board)
         .map(|m| format!("{}", m))
@@ -136,13 +171,27 @@ fn create_training_example(
     
     let top_moves_str: String = top_moves
         .iter()
-        .map(|m| format!("{}:{}", m.r#move, m.eval_cp))
+        .map(|m| format!("{}:{}", m.r#move, describe_score(m)))
         .collect::<Vec<_>>()
         .join(" | ");
-    
-    let best_move = &top_moves[0].r#move;
-    let best_eval = top_moves[0].eval_cp;
-    
+
+    let best = &top_moves[0];
+    let best_move = &best.r#move;
+    let best_score = describe_score(best);
+
+    let strength_line = match elo {
+        Some(target) => format!("- Target playing strength: {} Elo\n", target),
+        None => String::new(),
+    };
+
+    let wdl_line = match &best.wdl {
+        Some(wdl) => format!(
+            "- Win/draw/loss (per mille): {} / {} / {}\n",
+            wdl.win, wdl.draw, wdl.loss
+        ),
+        None => String::new(),
+    };
+
     let prompt = format!(
         "You are an expert chess player. Here is the position in FEN format:\n\
 {}\n\n\
@@ -151,20 +200,29 @@ Position analysis:\n\
 - Game phase: {}\n\
 - Material advantage: {:+}\n\
 - Mobility (legal moves): {}\n\
-- Top moves with evaluations: {}\n\n\
+- Top moves with evaluations: {}\n\
+{}{}\n\
 Select the best move. Keep your thinking brief, then output your chosen move.\n\
 Format:\n\
 <think>brief analysis</think>\n\
 <uci_move>your_move</uci_move>",
-        fen, legal_moves_str, phase, material, mobility, top_moves_str
+        fen, legal_moves_str, phase, material, mobility, top_moves_str, strength_line, wdl_line
     );
-    
+
+    let plan = if best.pv.len() > 1 {
+        let reply_plies = &best.pv[1..best.pv.len().min(3)];
+        format!(", then expecting {}", reply_plies.join(" "))
+    } else {
+        String::new()
+    };
+
     let response = format!(
-        "<think>Best move {} with eval {:+}cp. Material {:+}, mobility {}.</think><uci_move>{}</uci_move>",
-        best_move, best_eval, material, mobility, best_move
+        "<think>Best move {} with eval {}{}. Material {:+}, mobility {}.</think><uci_move>{}</uci_move>",
+        best_move, best_score, plan, material, mobility, best_move
     );
-    
+
     Ok(TrainingExample {
+        fen: fen.to_string(),
         messages: vec![
             Message {
                 role: "user".to_string(),
@@ -175,60 +233,285 @@ Format:\n\
                 content: response,
             },
         ],
+        elo,
     })
 }
 
+/// Maps a centipawn eval to a win probability via the standard logistic
+/// curve; mate scores (via `effective_cp`) saturate to ~0 or ~1 by sign.
+fn cp_to_win_prob(eval_cp: i32) -> f64 {
+    1.0 / (1.0 + 10f64.powf(-(eval_cp as f64) / 400.0))
+}
+
+/// Softmax over `top_moves`' centipawn evals at temperature `T`, i.e.
+/// `p_i ∝ exp(eval_i / T)`, renormalized across just the returned moves.
+fn softmax_policy(top_moves: &[TopMove], temperature: f64) -> Vec<PolicyEntry> {
+    let max_eval = top_moves.iter().map(effective_cp).max().unwrap_or(0) as f64;
+    let weights: Vec<f64> = top_moves
+        .iter()
+        .map(|m| ((effective_cp(m) as f64 - max_eval) / temperature).exp())
+        .collect();
+    let total: f64 = weights.iter().sum();
+
+    top_moves
+        .iter()
+        .zip(weights.iter())
+        .map(|(m, &w)| PolicyEntry {
+            r#move: m.r#move.clone(),
+            prob: w / total,
+        })
+        .collect()
+}
+
+fn create_policy_value_example(
+    board: &Board,
+    fen: &str,
+    phase: &str,
+    top_moves: &[TopMove],
+    temperature: f64,
+    elo: Option<u32>,
+) -> Result<PolicyValueExample> {
+    let legal_moves: Vec<String> = MoveGen::new_legal(board).map(|m| format!("{}", m)).collect();
+    let material = count_material(board);
+    let value = cp_to_win_prob(effective_cp(&top_moves[0]));
+    let policy = softmax_policy(top_moves, temperature);
+
+    Ok(PolicyValueExample {
+        fen: fen.to_string(),
+        phase: phase.to_string(),
+        value,
+        policy,
+        material,
+        elo,
+        legal_moves,
+    })
+}
+
+/// Extracts the resume key (position + Elo) from one already-written
+/// output line, regardless of output mode, so interrupted runs can tell
+/// which (fen, elo) pairs are already done.
+fn resume_key_from_json(line: &str) -> Option<u64> {
+    let value: serde_json::Value = serde_json::from_str(line).ok()?;
+    let fen = value.get("fen")?.as_str()?;
+    let board = Board::from_str(fen).ok()?;
+    let elo = value.get("elo").and_then(|v| v.as_u64()).map(|v| v as u32);
+    Some(cache::position_key(&board, elo))
+}
+
 fn main() -> Result<()> {
-    let args: Vec<String> = std::env::args().collect();
-    
-    if args.len() < 4 {
-        eprintln!("Usage: {} <input_positions.jsonl> <output_training.jsonl> <stockfish_path> [depth]", args[0]);
+    let mut args: Vec<String> = std::env::args().collect();
+    let program = args.remove(0);
+
+    let mut pgn_mode = false;
+    let mut pgn_dedup = false;
+    let mut pgn_sample_every: usize = 1;
+    let mut config = GenerationConfig::default();
+    let mut output_mode = OutputMode::Chat;
+    let mut policy_temperature: f64 = 150.0;
+    let mut cache_path: Option<String> = None;
+    let mut cache_reuse = true;
+
+    while let Some(flag) = args.first() {
+        match flag.as_str() {
+            "--pgn" => {
+                pgn_mode = true;
+                args.remove(0);
+            }
+            "--dedup" => {
+                pgn_dedup = true;
+                args.remove(0);
+            }
+            "--sample-every" => {
+                args.remove(0);
+                if args.is_empty() {
+                    eprintln!("--sample-every requires a value");
+                    std::process::exit(1);
+                }
+                pgn_sample_every = args.remove(0).parse().unwrap_or(1);
+            }
+            "--config" => {
+                args.remove(0);
+                if args.is_empty() {
+                    eprintln!("--config requires a path");
+                    std::process::exit(1);
+                }
+                let path = args.remove(0);
+                let contents = std::fs::read_to_string(&path)?;
+                config = serde_json::from_str(&contents)?;
+            }
+            "--threads" => {
+                args.remove(0);
+                config.engine.threads = args.remove(0).parse().ok();
+            }
+            "--hash" => {
+                args.remove(0);
+                config.engine.hash_mb = args.remove(0).parse().ok();
+            }
+            "--multipv" => {
+                args.remove(0);
+                config.engine.multipv = args.remove(0).parse().ok();
+            }
+            "--skill-level" => {
+                args.remove(0);
+                config.engine.skill_level = args.remove(0).parse().ok();
+            }
+            "--show-wdl" => {
+                config.engine.show_wdl = Some(true);
+                args.remove(0);
+            }
+            "--elo" => {
+                args.remove(0);
+                if let Ok(elo) = args.remove(0).parse() {
+                    config.elo_levels = vec![elo];
+                }
+            }
+            "--elo-sweep" => {
+                args.remove(0);
+                config.elo_levels = args
+                    .remove(0)
+                    .split(',')
+                    .filter_map(|s| s.trim().parse().ok())
+                    .collect();
+            }
+            "--output-mode" => {
+                args.remove(0);
+                output_mode = match args.remove(0).as_str() {
+                    "policy" | "policy-value" => OutputMode::PolicyValue,
+                    _ => OutputMode::Chat,
+                };
+            }
+            "--temperature" => {
+                args.remove(0);
+                policy_temperature = args.remove(0).parse().unwrap_or(150.0);
+            }
+            "--cache" => {
+                args.remove(0);
+                cache_path = Some(args.remove(0));
+            }
+            "--no-cache-reuse" => {
+                cache_reuse = false;
+                args.remove(0);
+            }
+            _ => break,
+        }
+    }
+
+    if args.len() < 3 {
+        eprintln!(
+            "Usage: {} [--pgn [--sample-every N] [--dedup]] [--config FILE] \
+             [--threads N] [--hash N] [--multipv N] [--skill-level N] [--show-wdl] \
+             [--elo N | --elo-sweep N,N,...] \
+             [--output-mode chat|policy] [--temperature T] \
+             [--cache FILE] [--no-cache-reuse] \
+             <input> <output_training.jsonl> <stockfish_path> [depth]",
+            program
+        );
         std::process::exit(1);
     }
-    
-    let input_file = &args[1];
-    let output_file = &args[2];
-    let stockfish_path = &args[3];
-    let depth: u8 = args.get(4).and_then(|s| s.parse().ok()).unwrap_or(7);
-    
+
+    let input_file = &args[0];
+    let output_file = &args[1];
+    let stockfish_path = &args[2];
+    let depth: u8 = args.get(3).and_then(|s| s.parse().ok()).unwrap_or(7);
+
     println!("Reading positions from: {}", input_file);
     println!("Output file: {}", output_file);
     println!("Stockfish path: {}", stockfish_path);
     println!("Analysis depth: {}", depth);
-    
-    let file = File::open(input_file)?;
-    let reader = BufReader::new(file);
-    
-    let positions: Vec<Position> = reader
-        .lines()
-        .filter_map(|line| line.ok())
-        .filter_map(|line| serde_json::from_str(&line).ok())
-        .collect();
-    
+
+    let positions: Vec<Position> = if pgn_mode {
+        println!("Input mode: PGN (sample_every={}, dedup={})", pgn_sample_every, pgn_dedup);
+        pgn::positions_from_pgn(input_file, pgn_sample_every, pgn_dedup)?
+    } else {
+        let file = File::open(input_file)?;
+        let reader = BufReader::new(file);
+
+        reader
+            .lines()
+            .filter_map(|line| line.ok())
+            .filter_map(|line| serde_json::from_str(&line).ok())
+            .collect()
+    };
+
     println!("Loaded {} positions", positions.len());
-    println!("Starting parallel analysis using {} threads...\n", rayon::current_num_threads());
-    
-    let pb = ProgressBar::new(positions.len() as u64);
+
+    let elo_levels: Vec<Option<u32>> = if config.elo_levels.is_empty() {
+        vec![None]
+    } else {
+        println!("Elo curriculum: {:?}", config.elo_levels);
+        config.elo_levels.iter().map(|&elo| Some(elo)).collect()
+    };
+
+    let resuming = Path::new(output_file).exists();
+    let mut done_keys: HashSet<u64> = HashSet::new();
+    if resuming {
+        let file = File::open(output_file)?;
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            if let Some(key) = resume_key_from_json(&line) {
+                done_keys.insert(key);
+            }
+        }
+        println!("Resuming: {} distinct (position, Elo) pairs already in {}", done_keys.len(), output_file);
+    }
+
+    let work_items: Vec<(&Position, Option<u32>)> = elo_levels
+        .iter()
+        .flat_map(|&elo| positions.iter().map(move |pos| (pos, elo)))
+        .filter(|(pos, elo)| match Board::from_str(&pos.fen) {
+            Ok(board) => !done_keys.contains(&cache::position_key(&board, *elo)),
+            Err(_) => true,
+        })
+        .collect();
+
+    println!("Starting parallel analysis of {} examples using {} threads...\n", work_items.len(), rayon::current_num_threads());
+
+    let pb = ProgressBar::new(work_items.len() as u64);
     pb.set_style(
         ProgressStyle::default_bar()
             .template("[{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({per_sec}, ETA: {eta})")?
             .progress_chars("#>-")
     );
-    
-    let output = Arc::new(Mutex::new(File::create(output_file)?));
+
+    let output_handle = if resuming {
+        OpenOptions::new().append(true).open(output_file)?
+    } else {
+        File::create(output_file)?
+    };
+    let output = Arc::new(Mutex::new(output_handle));
     let errors = Arc::new(Mutex::new(0));
-    
-    positions.par_iter().for_each(|pos| {
+    let cache = AnalysisCache::load(cache_path.as_deref(), cache_reuse)?;
+
+    work_items.par_iter().for_each(|(pos, elo)| {
         match Board::from_str(&pos.fen) {
             Ok(board) => {
-                match analyze_position(&pos.fen, stockfish_path, depth) {
+                let key = cache::position_key(&board, *elo);
+                let analysis = match cache.get(key) {
+                    Some(cached) => Ok(cached),
+                    None => analyze_position(&pos.fen, stockfish_path, depth, &config.engine, *elo)
+                        .map(|top_moves| {
+                            if !top_moves.is_empty() {
+                                cache.insert(key, &top_moves).ok();
+                            }
+                            top_moves
+                        }),
+                };
+                match analysis {
                     Ok(top_moves) if !top_moves.is_empty() => {
-                        match create_training_example(&board, &pos.fen, &pos.phase, &top_moves) {
-                            Ok(example) => {
-                                if let Ok(json) = serde_json::to_string(&example) {
-                                    let mut file = output.lock().unwrap();
-                                    writeln!(file, "{}", json).ok();
-                                }
+                        let json = match output_mode {
+                            OutputMode::Chat => {
+                                create_training_example(&board, &pos.fen, &pos.phase, &top_moves, *elo)
+                                    .and_then(|example| Ok(serde_json::to_string(&example)?))
+                            }
+                            OutputMode::PolicyValue => {
+                                create_policy_value_example(&board, &pos.fen, &pos.phase, &top_moves, policy_temperature, *elo)
+                                    .and_then(|example| Ok(serde_json::to_string(&example)?))
+                            }
+                        };
+                        match json {
+                            Ok(json) => {
+                                let mut file = output.lock().unwrap();
+                                writeln!(file, "{}", json).ok();
                             }
                             Err(_) => {
                                 *errors.lock().unwrap() += 1;
@@ -0,0 +1,223 @@
+use std::collections::HashSet;
+use std::str::FromStr;
+
+use anyhow::Result;
+use chess::{Board, BoardStatus, ChessMove, File as ChessFile, MoveGen, Piece, Square};
+
+use crate::Position;
+
+/// Splits a multi-game PGN file into per-game chunks, delimited by `[Event `
+/// tag pairs.
+fn split_games(content: &str) -> Vec<String> {
+    let mut games = Vec::new();
+    let mut current = String::new();
+
+    for line in content.lines() {
+        if line.starts_with("[Event ") && !current.trim().is_empty() {
+            games.push(std::mem::take(&mut current));
+        }
+        current.push_str(line);
+        current.push('\n');
+    }
+    if !current.trim().is_empty() {
+        games.push(current);
+    }
+
+    games
+}
+
+/// Strips `{...}` comments and `(...)` variations from movetext, tracking
+/// nesting depth so a variation containing a comment (or vice versa) is
+/// still removed correctly.
+fn strip_comments_and_variations(text: &str) -> String {
+    let mut out = String::new();
+    let mut brace_depth = 0u32;
+    let mut paren_depth = 0u32;
+
+    for c in text.chars() {
+        match c {
+            '{' => brace_depth += 1,
+            '}' => brace_depth = brace_depth.saturating_sub(1),
+            '(' => paren_depth += 1,
+            ')' => paren_depth = paren_depth.saturating_sub(1),
+            _ if brace_depth == 0 && paren_depth == 0 => out.push(c),
+            _ => {}
+        }
+    }
+
+    out
+}
+
+/// Turns cleaned movetext into a flat list of SAN tokens, dropping move
+/// numbers ("12." / "12..."), NAGs ("$1"), and result markers.
+fn tokenize_movetext(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .filter(|tok| !tok.starts_with('$'))
+        .filter(|tok| !matches!(*tok, "1-0" | "0-1" | "1/2-1/2" | "*"))
+        .map(|tok| match tok.rfind('.') {
+            Some(idx) if tok[..=idx].chars().all(|c| c.is_ascii_digit() || c == '.') => {
+                tok[idx + 1..].to_string()
+            }
+            _ => tok.to_string(),
+        })
+        .filter(|tok| !tok.is_empty())
+        .collect()
+}
+
+fn castle_move(board: &Board, kingside: bool) -> Option<ChessMove> {
+    let king_sq = board.king_square(board.side_to_move());
+    MoveGen::new_legal(board).find(|mv| {
+        if mv.get_source() != king_sq {
+            return false;
+        }
+        let delta =
+            mv.get_dest().get_file().to_index() as i32 - king_sq.get_file().to_index() as i32;
+        if kingside {
+            delta == 2
+        } else {
+            delta == -2
+        }
+    })
+}
+
+fn promotion_piece(letter: &str) -> Option<Piece> {
+    match letter {
+        "Q" => Some(Piece::Queen),
+        "R" => Some(Piece::Rook),
+        "B" => Some(Piece::Bishop),
+        "N" => Some(Piece::Knight),
+        _ => None,
+    }
+}
+
+/// Resolves a single SAN token (e.g. `Nf3`, `exd5`, `O-O`, `e8=Q+`) against
+/// the legal moves of `board`. Returns `None` if the token can't be matched,
+/// which ends the game's walk early.
+fn san_to_move(board: &Board, token: &str) -> Option<ChessMove> {
+    let san = token.trim_end_matches(['+', '#', '!', '?']);
+
+    if matches!(san, "O-O" | "0-0") {
+        return castle_move(board, true);
+    }
+    if matches!(san, "O-O-O" | "0-0-0") {
+        return castle_move(board, false);
+    }
+
+    let (san, promotion) = match san.find('=') {
+        Some(idx) => (&san[..idx], promotion_piece(&san[idx + 1..idx + 2])),
+        None => (san, None),
+    };
+
+    let chars: Vec<char> = san.chars().collect();
+    let (piece, rest) = match chars.first() {
+        Some('K') => (Piece::King, chars[1..].iter().collect::<String>()),
+        Some('Q') => (Piece::Queen, chars[1..].iter().collect::<String>()),
+        Some('R') => (Piece::Rook, chars[1..].iter().collect::<String>()),
+        Some('B') => (Piece::Bishop, chars[1..].iter().collect::<String>()),
+        Some('N') => (Piece::Knight, chars[1..].iter().collect::<String>()),
+        _ => (Piece::Pawn, san.to_string()),
+    };
+    let rest = rest.replace('x', "");
+    if rest.len() < 2 {
+        return None;
+    }
+
+    let dest_str = &rest[rest.len() - 2..];
+    let dest = Square::from_str(dest_str).ok()?;
+    let disambiguation = &rest[..rest.len() - 2];
+
+    MoveGen::new_legal(board)
+        .filter(|mv| mv.get_dest() == dest)
+        .filter(|mv| board.piece_on(mv.get_source()) == Some(piece))
+        .filter(|mv| mv.get_promotion() == promotion)
+        .find(|mv| {
+            let src = mv.get_source();
+            disambiguation.chars().all(|c| {
+                if c.is_ascii_lowercase() {
+                    ChessFile::from_str(&c.to_string()).map(|f| f == src.get_file()).unwrap_or(true)
+                } else if c.is_ascii_digit() {
+                    format!("{}", src).ends_with(c)
+                } else {
+                    true
+                }
+            })
+        })
+}
+
+/// Material-from-move-number phase heuristic shared with `count_material`'s
+/// piece values, but summed across both sides rather than side-relative.
+fn derive_phase(board: &Board, fullmove_number: u32) -> String {
+    let piece_values = [
+        (Piece::Pawn, 1),
+        (Piece::Knight, 3),
+        (Piece::Bishop, 3),
+        (Piece::Rook, 5),
+        (Piece::Queen, 9),
+    ];
+    let total_material: i32 = piece_values
+        .iter()
+        .map(|(piece, value)| board.pieces(*piece).popcnt() as i32 * value)
+        .sum();
+
+    if fullmove_number <= 10 && total_material >= 60 {
+        "opening"
+    } else if total_material <= 14 {
+        "endgame"
+    } else {
+        "middlegame"
+    }
+    .to_string()
+}
+
+/// Walks every game in a PGN file move-by-move, emitting a `Position` at
+/// each sampled ply. `sample_every` of 1 emits every ply; `dedup` drops FENs
+/// already emitted earlier in the file (e.g. repeated transpositions or
+/// duplicate games).
+pub fn positions_from_pgn(path: &str, sample_every: usize, dedup: bool) -> Result<Vec<Position>> {
+    let content = std::fs::read_to_string(path)?;
+    let sample_every = sample_every.max(1);
+
+    let mut positions = Vec::new();
+    let mut seen = HashSet::new();
+
+    for game_text in split_games(&content) {
+        let movetext: String = game_text
+            .lines()
+            .filter(|line| !line.trim_start().starts_with('['))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let movetext = strip_comments_and_variations(&movetext);
+        let sans = tokenize_movetext(&movetext);
+
+        let mut board = Board::default();
+        let mut fullmove_number = 1u32;
+
+        for (ply, san) in sans.iter().enumerate() {
+            let mv = match san_to_move(&board, san) {
+                Some(mv) => mv,
+                None => break,
+            };
+            board = board.make_move_new(mv);
+            if ply % 2 == 1 {
+                fullmove_number += 1;
+            }
+
+            if board.status() != BoardStatus::Ongoing {
+                continue;
+            }
+            if ply % sample_every != 0 {
+                continue;
+            }
+
+            let fen = board.to_string();
+            if dedup && !seen.insert(fen.clone()) {
+                continue;
+            }
+
+            let phase = derive_phase(&board, fullmove_number);
+            positions.push(Position { fen, phase });
+        }
+    }
+
+    Ok(positions)
+}